@@ -0,0 +1,113 @@
+use anyhow::{bail, Context};
+
+/// A parsed `magnet:` URI. The info hash is taken straight from the `xt` parameter
+/// rather than from hashing an info dictionary, so peer discovery can happen without
+/// ever fetching a `.torrent` file.
+#[derive(Debug, Clone)]
+pub struct Magnet {
+    pub info_hash: [u8; 20],
+    pub name: Option<String>,
+    pub trackers: Vec<String>,
+}
+
+impl Magnet {
+    /// Parse a magnet link, extracting `xt=urn:btih:<infohash>`, an optional
+    /// `dn=<name>`, and any number of `tr=<tracker>` parameters.
+    pub fn parse(link: &str) -> anyhow::Result<Self> {
+        let query = link
+            .strip_prefix("magnet:?")
+            .context("not a magnet link")?;
+
+        let mut info_hash = None;
+        let mut name = None;
+        let mut trackers = Vec::new();
+
+        for pair in query.split('&') {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            match key {
+                "xt" => {
+                    let hash = value
+                        .strip_prefix("urn:btih:")
+                        .context("xt is not a btih urn")?;
+                    info_hash = Some(decode_info_hash(hash)?);
+                }
+                "dn" => name = Some(percent_decode(value)),
+                "tr" => trackers.push(percent_decode(value)),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            info_hash: info_hash.context("magnet link is missing an info hash")?,
+            name,
+            trackers,
+        })
+    }
+}
+
+/// Decode the info hash carried by a magnet link: either 40 hex characters or a
+/// 32-character RFC 4648 base32 string, both yielding 20 bytes.
+fn decode_info_hash(hash: &str) -> anyhow::Result<[u8; 20]> {
+    match hash.len() {
+        40 => {
+            let bytes = hex::decode(hash).context("decode hex info hash")?;
+            bytes
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("info hash was not 20 bytes"))
+        }
+        32 => base32_decode(hash),
+        n => bail!("unexpected info hash length: {n}"),
+    }
+}
+
+/// Decode a 32-character base32 (RFC 4648, uppercase) string into a 20-byte hash.
+fn base32_decode(s: &str) -> anyhow::Result<[u8; 20]> {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let mut out = Vec::with_capacity(20);
+    let mut buffer: u32 = 0;
+    let mut bits = 0u32;
+    for c in s.bytes() {
+        let c = c.to_ascii_uppercase();
+        let value = ALPHABET
+            .iter()
+            .position(|&a| a == c)
+            .with_context(|| format!("invalid base32 character {:?}", c as char))?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    out.try_into()
+        .map_err(|_| anyhow::anyhow!("base32 info hash was not 20 bytes"))
+}
+
+/// Minimal percent-decoding for magnet query values (`%XX` escapes and `+` spaces).
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                if let Ok(byte) = u8::from_str_radix(&s[i + 1..i + 3], 16) {
+                    out.push(byte);
+                    i += 3;
+                    continue;
+                }
+                out.push(bytes[i]);
+                i += 1;
+            }
+            b'+' => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
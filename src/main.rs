@@ -12,20 +12,38 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 mod decode;
 mod hand;
 mod hashes;
+mod magnet;
+mod message;
 mod tracker;
+mod udp_tracker;
 
 use decode::decode_bencoded_value;
 use hashes::Hashes;
 use tracker::TrackerRequest;
 
 use crate::hand::HandShake;
+use crate::message::{Message, MessageFramer, MessageTag, Piece, Request};
 use crate::tracker::TrackerResponse;
 
+use futures_util::{SinkExt, StreamExt};
+use tokio_util::codec::Framed;
+
+/// Blocks are requested in fixed 16 KiB chunks (the last block of a piece may be shorter).
+const BLOCK_MAX: usize = 1 << 14;
+
+/// The peer id this client advertises to trackers and peers.
+const MY_PEER_ID: &[u8; 20] = b"00112233445566778899";
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 struct Torrent {
     // The URL of the tracker.
     announce: String,
 
+    // An optional list of tracker tiers (BEP 12), tried in order as fallbacks when
+    // the primary `announce` tracker does not answer.
+    #[serde(default, rename = "announce-list", skip_serializing_if = "Vec::is_empty")]
+    announce_list: Vec<Vec<String>>,
+
     // This maps to a dictionary, with keys described below.
     info: Info,
 }
@@ -66,7 +84,71 @@ struct File {
     path: Vec<String>,
 }
 
+/// Where a single file lives inside the torrent's concatenated byte stream:
+/// its on-disk relative path, its start offset across all files, and its length.
+#[derive(Debug, Clone)]
+struct FileLayout {
+    path: PathBuf,
+    offset: usize,
+    length: usize,
+}
+
 impl Torrent {
+    /// The total number of bytes described by the torrent, summing every file in
+    /// the multi-file case.
+    pub fn total_length(&self) -> usize {
+        match &self.info.key {
+            Keys::SingleFile { length } => *length,
+            Keys::MultiFile { files } => files.iter().map(|f| f.length).sum(),
+        }
+    }
+
+    /// The files that make up the download, each annotated with its absolute start
+    /// offset across the concatenated stream. A full download maps a global piece
+    /// offset onto this list, spilling into the next file at a boundary.
+    pub fn files(&self) -> Vec<FileLayout> {
+        match &self.info.key {
+            Keys::SingleFile { length } => vec![FileLayout {
+                path: PathBuf::from(&self.info.name),
+                offset: 0,
+                length: *length,
+            }],
+            Keys::MultiFile { files } => {
+                let mut offset = 0;
+                files
+                    .iter()
+                    .map(|f| {
+                        let mut path = PathBuf::from(&self.info.name);
+                        path.extend(&f.path);
+                        let layout = FileLayout {
+                            path,
+                            offset,
+                            length: f.length,
+                        };
+                        offset += f.length;
+                        layout
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// The true size in bytes of piece `piece`; the final piece may be shorter than
+    /// `plength`.
+    pub fn piece_size(&self, piece: usize) -> usize {
+        let npieces = self.info.pieces.0.len();
+        if piece == npieces - 1 {
+            let rem = self.total_length() % self.info.plength;
+            if rem == 0 {
+                self.info.plength
+            } else {
+                rem
+            }
+        } else {
+            self.info.plength
+        }
+    }
+
     pub fn info_hash(&self) -> [u8; 20] {
         let info_encode = serde_bencode::to_bytes(&self.info).expect("re-encode the info section");
         let mut hasher = Sha1::new();
@@ -91,6 +173,18 @@ enum Commands {
     Info { torrent: PathBuf },
     Peers { torrent: PathBuf },
     Handshake { torrent: PathBuf, peer: String },
+    DownloadPiece {
+        torrent: PathBuf,
+        output: PathBuf,
+        piece: usize,
+    },
+    Download {
+        torrent: PathBuf,
+        output: PathBuf,
+    },
+    Magnet {
+        link: String,
+    },
 }
 // Usage: your_bittorrent.sh decode "<encoded_value>"
 #[tokio::main]
@@ -110,11 +204,17 @@ async fn main() -> anyhow::Result<()> {
             let t: Torrent = serde_bencode::from_bytes(&content).context("parse torrent file")?;
             println!("{t:?}");
             println!("{}", t.announce);
-            if let Keys::SingleFile { length } = t.info.key {
-                length
-            } else {
-                todo!();
-            };
+            match &t.info.key {
+                Keys::SingleFile { length } => {
+                    println!("Length: {length}");
+                }
+                Keys::MultiFile { files } => {
+                    println!("Files:");
+                    for file in files {
+                        println!("{} ({} bytes)", file.path.join("/"), file.length);
+                    }
+                }
+            }
             let info_hash = t.info_hash();
             println!("Info hash: {}", hex::encode(info_hash));
             println!("Piece length: {}", t.info.plength);
@@ -126,35 +226,10 @@ async fn main() -> anyhow::Result<()> {
         Commands::Peers { torrent } => {
             let content = std::fs::read(torrent).context("reading torrent file")?;
             let t: Torrent = serde_bencode::from_bytes(&content).context("decode the content")?;
-            let length = if let Keys::SingleFile { length } = t.info.key {
-                length
-            } else {
-                todo!();
-            };
 
             let info_hash = t.info_hash();
-
-            let request = TrackerRequest {
-                peer_id: String::from("00112233445566778899"),
-                port: 6881,
-                uploaded: 0,
-                downloaded: 0,
-                left: length,
-                compact: 1,
-            };
-            let url_params =
-                serde_urlencoded::to_string(&request).context("url-encode tracker parameters")?;
-            let tracker_url = format!(
-                "{}?{}&info_hash={}",
-                t.announce,
-                url_params,
-                &urlencode(&info_hash)
-            );
-            let response = reqwest::get(tracker_url).await.context("query tracker")?;
-            let response = response.bytes().await.context("fetch tracker response")?;
-            let response: TrackerResponse =
-                serde_bencode::from_bytes(&response).context("parse tracker response")?;
-            for peer in &response.peers.0 {
+            let peers = get_peers(&t, &info_hash).await?;
+            for peer in &peers.0 {
                 println!("{}:{}", peer.ip(), peer.port());
             }
         }
@@ -189,10 +264,346 @@ async fn main() -> anyhow::Result<()> {
             assert_eq!(&handshake.bittorrent, b"BitTorrent protocol");
             println!("Peer ID: {}", hex::encode(&handshake.peer_id));
         }
+        Commands::DownloadPiece {
+            torrent,
+            output,
+            piece,
+        } => {
+            let content = std::fs::read(torrent).context("reading torrent file")?;
+            let t: Torrent = serde_bencode::from_bytes(&content).context("decode the content")?;
+
+            assert!(piece < t.info.pieces.0.len());
+
+            let info_hash = t.info_hash();
+            let peers = get_peers(&t, &info_hash).await?;
+            let addr = peers.0.first().context("tracker returned no peers")?;
+
+            let mut peer = peer_handshake(addr, &info_hash).await?;
+            peer_ready(&mut peer).await?;
+
+            let all_blocks = download_piece(&mut peer, &t, piece).await?;
+
+            tokio::fs::write(&output, &all_blocks)
+                .await
+                .context("write downloaded piece")?;
+            println!("Piece {piece} downloaded to {}.", output.display());
+        }
+        Commands::Download { torrent, output } => {
+            let content = std::fs::read(torrent).context("reading torrent file")?;
+            let t: Torrent = serde_bencode::from_bytes(&content).context("decode the content")?;
+            let t = std::sync::Arc::new(t);
+
+            let info_hash = t.info_hash();
+            let peers = get_peers(&t, &info_hash).await?;
+
+            let npieces = t.info.pieces.0.len();
+            let state = std::sync::Arc::new(State {
+                queue: tokio::sync::Mutex::new((0..npieces).collect()),
+                output: tokio::sync::Mutex::new(vec![None; npieces]),
+            });
+
+            // One task per peer; they race to drain the shared work queue.
+            let tasks = peers.0.into_iter().map(|peer| {
+                let state = std::sync::Arc::clone(&state);
+                let t = std::sync::Arc::clone(&t);
+                tokio::spawn(peer_worker(state, t, peer))
+            });
+            futures_util::future::join_all(tasks).await;
+
+            // Every piece must have checked out; bail if the swarm left a hole.
+            let pieces = std::sync::Arc::try_unwrap(state)
+                .ok()
+                .expect("all peer tasks have finished")
+                .output
+                .into_inner();
+            let mut buffer = Vec::with_capacity(t.total_length());
+            for (i, piece) in pieces.into_iter().enumerate() {
+                let piece = piece.with_context(|| format!("piece {i} was never downloaded"))?;
+                buffer.extend_from_slice(&piece);
+            }
+
+            // Map the concatenated byte stream onto the torrent's files, splitting at
+            // file boundaries (a piece may straddle two files).
+            let files = t.files();
+            if files.len() == 1 {
+                tokio::fs::write(&output, &buffer)
+                    .await
+                    .context("write downloaded file")?;
+            } else {
+                for file in files {
+                    let path = output.join(&file.path);
+                    if let Some(parent) = path.parent() {
+                        tokio::fs::create_dir_all(parent)
+                            .await
+                            .context("create output directory")?;
+                    }
+                    tokio::fs::write(&path, &buffer[file.offset..file.offset + file.length])
+                        .await
+                        .with_context(|| format!("write {}", path.display()))?;
+                }
+            }
+            println!(
+                "Downloaded {} to {}.",
+                t.info.name,
+                output.display()
+            );
+        }
+        Commands::Magnet { link } => {
+            let magnet = magnet::Magnet::parse(&link).context("parse magnet link")?;
+
+            println!("Info hash: {}", hex::encode(magnet.info_hash));
+            if let Some(name) = &magnet.name {
+                println!("Name: {name}");
+            }
+
+            // Without an info dict we do not know the file size yet, so report nothing
+            // left to download and let the tracker hand us peers anyway.
+            let mut last_err = None;
+            for tracker in &magnet.trackers {
+                match query_tracker(tracker, &magnet.info_hash, 0).await {
+                    core::result::Result::Ok(peers) => {
+                        for peer in &peers.0 {
+                            println!("{}:{}", peer.ip(), peer.port());
+                        }
+                        last_err = None;
+                        break;
+                    }
+                    Err(e) => {
+                        eprintln!("tracker {tracker} failed: {e:#}");
+                        last_err = Some(e);
+                    }
+                }
+            }
+            if let Some(e) = last_err {
+                return Err(e);
+            }
+        }
     }
     Ok(())
 }
 
+type PeerStream = Framed<tokio::net::TcpStream, MessageFramer>;
+
+/// Ask a single tracker (HTTP or UDP) for the peers serving the torrent identified by
+/// `info_hash`. The hash is supplied by the caller so this works for both `.torrent`
+/// files and magnet links.
+async fn query_tracker(
+    announce: &str,
+    info_hash: &[u8; 20],
+    left: usize,
+) -> anyhow::Result<tracker::peer::Peers> {
+    if announce.starts_with("udp://") {
+        udp_tracker::announce(announce, info_hash, MY_PEER_ID, left, 6881)
+            .await
+            .context("query udp tracker")
+    } else {
+        let request = TrackerRequest {
+            peer_id: String::from("00112233445566778899"),
+            port: 6881,
+            uploaded: 0,
+            downloaded: 0,
+            left,
+            compact: 1,
+        };
+        let url_params =
+            serde_urlencoded::to_string(&request).context("url-encode tracker parameters")?;
+        let tracker_url = format!(
+            "{}?{}&info_hash={}",
+            announce,
+            url_params,
+            &urlencode(info_hash)
+        );
+        let response = reqwest::get(tracker_url).await.context("query tracker")?;
+        let response = response.bytes().await.context("fetch tracker response")?;
+        let response: TrackerResponse =
+            serde_bencode::from_bytes(&response).context("parse tracker response")?;
+        Ok(response.peers)
+    }
+}
+
+/// Ask the torrent's trackers for peers, falling back through `announce-list` tiers in
+/// order if the primary `announce` tracker does not answer.
+async fn get_peers(t: &Torrent, info_hash: &[u8; 20]) -> anyhow::Result<tracker::peer::Peers> {
+    let left = t.total_length();
+    let candidates = std::iter::once(&t.announce).chain(t.announce_list.iter().flatten());
+
+    let mut last_err = None;
+    for announce in candidates {
+        match query_tracker(announce, info_hash, left).await {
+            core::result::Result::Ok(peers) => return Ok(peers),
+            Err(e) => {
+                eprintln!("tracker {announce} failed: {e:#}");
+                last_err = Some(e);
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| anyhow::anyhow!("no trackers configured")))
+}
+
+/// Open a TCP connection to `peer`, exchange handshakes, and wrap it in the framed
+/// message codec ready for the post-handshake protocol.
+async fn peer_handshake(
+    peer: &SocketAddrV4,
+    info_hash: &[u8; 20],
+) -> anyhow::Result<PeerStream> {
+    let mut stream = tokio::net::TcpStream::connect(peer)
+        .await
+        .context("connect to peer")?;
+
+    let mut handshake = HandShake::new(*info_hash, *MY_PEER_ID);
+    {
+        let handshake_bytes =
+            &mut handshake as *mut HandShake as *mut [u8; std::mem::size_of::<HandShake>()];
+        // Safety: Handshake is a POD with repr(c)
+        let handshake_bytes: &mut [u8; std::mem::size_of::<HandShake>()] =
+            unsafe { &mut *handshake_bytes };
+        stream
+            .write_all(handshake_bytes)
+            .await
+            .context("write handshake")?;
+        stream
+            .read_exact(handshake_bytes)
+            .await
+            .context("read handshake")?;
+    }
+    assert_eq!(handshake.length, 19);
+    assert_eq!(&handshake.bittorrent, b"BitTorrent protocol");
+
+    Ok(Framed::new(stream, MessageFramer))
+}
+
+/// Complete the pre-download dance: read the peer's bitfield, declare interest, and
+/// wait to be unchoked. Returns the raw bitfield payload so the caller can tell which
+/// pieces this peer can serve.
+async fn peer_ready(peer: &mut PeerStream) -> anyhow::Result<Vec<u8>> {
+    let bitfield = peer
+        .next()
+        .await
+        .context("peer hung up before bitfield")?
+        .context("peer message was invalid")?;
+    anyhow::ensure!(bitfield.tag == MessageTag::Bitfield, "expected bitfield");
+
+    peer.send(Message {
+        tag: MessageTag::Interested,
+        payload: Vec::new(),
+    })
+    .await
+    .context("send interested message")?;
+
+    let unchoke = peer
+        .next()
+        .await
+        .context("peer hung up before unchoke")?
+        .context("peer message was invalid")?;
+    anyhow::ensure!(unchoke.tag == MessageTag::Unchoke, "expected unchoke");
+
+    Ok(bitfield.payload)
+}
+
+/// Whether the bitfield advertises piece `piece`. Bits are big-endian within each byte.
+fn bitfield_has(bitfield: &[u8], piece: usize) -> bool {
+    let byte = piece / 8;
+    let bit = 7 - (piece % 8);
+    bitfield.get(byte).is_some_and(|b| b & (1 << bit) != 0)
+}
+
+/// Download piece `piece` over an already-unchoked peer, in 16 KiB blocks, and verify
+/// its SHA1 against the torrent's piece hashes.
+async fn download_piece(peer: &mut PeerStream, t: &Torrent, piece: usize) -> anyhow::Result<Vec<u8>> {
+    let piece_size = t.piece_size(piece);
+    let nblocks = (piece_size + (BLOCK_MAX - 1)) / BLOCK_MAX;
+
+    let mut all_blocks = Vec::with_capacity(piece_size);
+    for block in 0..nblocks {
+        let begin = block * BLOCK_MAX;
+        let block_size = (piece_size - begin).min(BLOCK_MAX);
+
+        let request = Request::new(piece as u32, begin as u32, block_size as u32);
+        peer.send(Message {
+            tag: MessageTag::Request,
+            payload: request.as_bytes().to_vec(),
+        })
+        .await
+        .with_context(|| format!("send request for block {block}"))?;
+
+        let piece_msg = peer
+            .next()
+            .await
+            .context("peer hung up mid-piece")?
+            .context("peer message was invalid")?;
+        anyhow::ensure!(piece_msg.tag == MessageTag::Piece, "expected piece");
+        anyhow::ensure!(!piece_msg.payload.is_empty(), "empty piece payload");
+
+        let piece_msg = Piece::from_bytes(&piece_msg.payload);
+        anyhow::ensure!(piece_msg.index() as usize == piece, "wrong piece index");
+        anyhow::ensure!(piece_msg.begin() as usize == begin, "wrong block offset");
+        all_blocks.extend_from_slice(piece_msg.block());
+    }
+
+    anyhow::ensure!(all_blocks.len() == piece_size, "short piece");
+
+    let mut hasher = Sha1::new();
+    hasher.update(&all_blocks);
+    let hash: [u8; 20] = hasher
+        .finalize()
+        .try_into()
+        .expect("GenericArray<_, 20> == [_; 20]");
+    anyhow::ensure!(hash == t.info.pieces.0[piece], "piece hash did not match");
+
+    Ok(all_blocks)
+}
+
+/// Shared state driving the concurrent whole-file download: a queue of piece indices
+/// still needing a home, and the assembled output slots indexed by piece.
+struct State {
+    queue: tokio::sync::Mutex<Vec<usize>>,
+    output: tokio::sync::Mutex<Vec<Option<Vec<u8>>>>,
+}
+
+/// One peer task: handshake, wait for readiness, then repeatedly take a piece the peer
+/// actually has, download it, and store it — requeuing anything that fails.
+async fn peer_worker(state: std::sync::Arc<State>, t: std::sync::Arc<Torrent>, peer: SocketAddrV4) {
+    let info_hash = t.info_hash();
+    let mut stream = match peer_handshake(&peer, &info_hash).await {
+        core::result::Result::Ok(s) => s,
+        Err(e) => {
+            eprintln!("peer {peer}: handshake failed: {e:#}");
+            return;
+        }
+    };
+    let bitfield = match peer_ready(&mut stream).await {
+        core::result::Result::Ok(b) => b,
+        Err(e) => {
+            eprintln!("peer {peer}: not ready: {e:#}");
+            return;
+        }
+    };
+
+    loop {
+        // Take the first queued piece this peer can serve.
+        let piece = {
+            let mut queue = state.queue.lock().await;
+            let pos = queue.iter().position(|&p| bitfield_has(&bitfield, p));
+            match pos {
+                Some(pos) => queue.remove(pos),
+                None => return,
+            }
+        };
+
+        match download_piece(&mut stream, &t, piece).await {
+            core::result::Result::Ok(bytes) => {
+                state.output.lock().await[piece] = Some(bytes);
+            }
+            Err(e) => {
+                eprintln!("peer {peer}: piece {piece} failed: {e:#}");
+                // Hand the piece back and drop this peer; it likely disconnected.
+                state.queue.lock().await.push(piece);
+                return;
+            }
+        }
+    }
+}
+
 fn urlencode(t: &[u8; 20]) -> String {
     let mut encoded = String::with_capacity(3 * t.len());
     for &byte in t {
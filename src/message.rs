@@ -0,0 +1,169 @@
+use bytes::{Buf, BufMut, BytesMut};
+use std::io;
+use tokio_util::codec::{Decoder, Encoder};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MessageTag {
+    Choke = 0,
+    Unchoke = 1,
+    Interested = 2,
+    NotInterested = 3,
+    Have = 4,
+    Bitfield = 5,
+    Request = 6,
+    Piece = 7,
+    Cancel = 8,
+}
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub tag: MessageTag,
+    pub payload: Vec<u8>,
+}
+
+/// A `Request` payload: the piece index, the byte offset of this block within the
+/// piece, and the block length, each a 4-byte big-endian integer.
+#[repr(C)]
+pub struct Request {
+    index: [u8; 4],
+    begin: [u8; 4],
+    length: [u8; 4],
+}
+
+impl Request {
+    pub fn new(index: u32, begin: u32, length: u32) -> Self {
+        Self {
+            index: index.to_be_bytes(),
+            begin: begin.to_be_bytes(),
+            length: length.to_be_bytes(),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        let bytes = self as *const Self as *const [u8; std::mem::size_of::<Self>()];
+        // Safety: Request is a POD with repr(c)
+        let bytes: &[u8; std::mem::size_of::<Self>()] = unsafe { &*bytes };
+        bytes
+    }
+}
+
+/// A `Piece` payload: the piece index and block offset followed by the raw block bytes.
+#[repr(C)]
+pub struct Piece<T: ?Sized = [u8]> {
+    index: [u8; 4],
+    begin: [u8; 4],
+    block: T,
+}
+
+impl Piece {
+    pub fn index(&self) -> u32 {
+        u32::from_be_bytes(self.index)
+    }
+
+    pub fn begin(&self) -> u32 {
+        u32::from_be_bytes(self.begin)
+    }
+
+    pub fn block(&self) -> &[u8] {
+        &self.block
+    }
+
+    pub fn from_bytes(data: &[u8]) -> &Self {
+        let n = data.len();
+        // NOTE: the trailing `[u8]` is dynamically sized, so we build a fat pointer
+        // whose metadata is the length of the block that follows the two headers.
+        let piece = &data[..n - std::mem::size_of::<Piece<()>>()] as *const [u8] as *const Piece;
+        // Safety: Piece is a POD with repr(c), and the fat pointer metadata lines up.
+        unsafe { &*piece }
+    }
+}
+
+/// A framed peer-message codec: every message is a 4-byte big-endian length prefix,
+/// a 1-byte tag, and a variable-length payload. A length of zero is a keep-alive.
+pub struct MessageFramer;
+
+const MAX: usize = 1 << 16;
+
+impl Decoder for MessageFramer {
+    type Item = Message;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.len() < 4 {
+            // not enough for the length marker
+            return Ok(None);
+        }
+
+        let mut length_bytes = [0u8; 4];
+        length_bytes.copy_from_slice(&src[..4]);
+        let length = u32::from_be_bytes(length_bytes) as usize;
+
+        if length == 0 {
+            // keep-alive message
+            src.advance(4);
+            return self.decode(src);
+        }
+
+        if src.len() < 5 {
+            // not enough for the tag byte
+            return Ok(None);
+        }
+
+        if length > MAX {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("frame of length {length} is too large."),
+            ));
+        }
+
+        if src.len() < 4 + length {
+            // the full frame has not arrived yet
+            src.reserve(4 + length - src.len());
+            return Ok(None);
+        }
+
+        let tag = match src[4] {
+            0 => MessageTag::Choke,
+            1 => MessageTag::Unchoke,
+            2 => MessageTag::Interested,
+            3 => MessageTag::NotInterested,
+            4 => MessageTag::Have,
+            5 => MessageTag::Bitfield,
+            6 => MessageTag::Request,
+            7 => MessageTag::Piece,
+            8 => MessageTag::Cancel,
+            tag => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown message tag {tag}."),
+                ))
+            }
+        };
+        let payload = src[5..4 + length].to_vec();
+        src.advance(4 + length);
+
+        Ok(Some(Message { tag, payload }))
+    }
+}
+
+impl Encoder<Message> for MessageFramer {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Message, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        if item.payload.len() + 1 > MAX {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("frame of length {} is too large.", item.payload.len()),
+            ));
+        }
+
+        let len = item.payload.len() as u32 + 1;
+        dst.reserve(4 + len as usize);
+        dst.put_u32(len);
+        dst.put_u8(item.tag as u8);
+        dst.extend_from_slice(&item.payload);
+
+        Ok(())
+    }
+}
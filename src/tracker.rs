@@ -38,7 +38,7 @@ pub struct TrackerResponse {
     pub peers: Peers,
 }
 
-mod peer {
+pub mod peer {
     use serde::de::{self, Deserializer, Visitor};
     use serde::ser::Serializer;
     use serde::{Deserialize, Serialize};